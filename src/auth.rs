@@ -0,0 +1,66 @@
+use crate::db;
+use crate::errors::AppError;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::future::Future;
+use std::pin::Pin;
+
+const TOKEN_LENGTH: usize = 48;
+
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill(&mut salt);
+    let config = argon2::Config::default();
+    Ok(argon2::hash_encoded(password.as_bytes(), &salt, &config)?)
+}
+
+pub fn verify_password(hash: &str, password: &str) -> Result<bool, AppError> {
+    Ok(argon2::verify_encoded(hash, password.as_bytes())?)
+}
+
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::to_string)
+}
+
+/// Extractor that resolves a `Authorization: Bearer <token>` header to the
+/// owning [`db::User`], failing with [`AppError::Unauthorized`] if the
+/// header is missing or the token is unknown. Wrap it in `Option<AuthedUser>`
+/// for endpoints where authentication is optional.
+#[derive(Debug, Clone)]
+pub struct AuthedUser(pub db::User);
+
+impl FromRequest for AuthedUser {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, AppError>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<db::Pool>>().cloned();
+        let token = bearer_token(req);
+
+        Box::pin(async move {
+            let pool = pool.ok_or(AppError::Unauthorized)?;
+            let token = token.ok_or(AppError::Unauthorized)?;
+
+            let user = db::get_user_by_token(&pool, &token)
+                .await?
+                .ok_or(AppError::Unauthorized)?;
+
+            Ok(AuthedUser(user))
+        })
+    }
+}
@@ -1,57 +1,462 @@
-use rand::distributions::Alphanumeric;
-use rand::Rng;
+use crate::errors::AppError;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use rand::RngCore;
+use rusqlite::params;
 use rusqlite::OptionalExtension;
-use rusqlite::NO_PARAMS;
 
 pub type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
 pub type Connection = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
 
+// URL-safe alphabet, excluding visually ambiguous characters (0/O/o, 1/I/l/L).
+const ALPHABET: &[char] = &[
+    '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'J', 'K', 'M',
+    'N', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'a', 'b', 'c', 'd', 'e', 'f', 'g',
+    'h', 'i', 'j', 'k', 'm', 'n', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z',
+];
 
-pub async fn create_table(conn: &Connection) -> Result<usize, rusqlite::Error> {
-    conn.execute(
-        "
-        create table if not exists urls (
-            id text primary key,
-            url text not null unique,
-            hits bigint default 0
-        )
-        ",
-        NO_PARAMS,
-    )
+// How many times to retry generating a fresh id before giving up.
+const MAX_ID_GENERATION_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub password_hash: String,
+}
+
+/// Flattens the result of a `web::block` call: a panicked/dropped blocking
+/// task becomes [`AppError::BlockingCanceled`], anything else is the error
+/// the blocking closure itself returned.
+fn flatten_blocking<T>(result: Result<T, BlockingError<AppError>>) -> Result<T, AppError> {
+    match result {
+        Ok(v) => Ok(v),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(AppError::BlockingCanceled),
+    }
 }
 
-fn generate_id() -> String {
+// Uniform, unbiased id generation (nanoid-style rejection sampling): pick the
+// smallest bitmask covering the alphabet, draw random bytes and keep only
+// those that land inside the alphabet, discarding (and re-drawing for) the rest.
+fn generate_id(len: usize) -> String {
     let mut rng = rand::thread_rng();
-    (&mut rng)
-        .sample_iter(Alphanumeric)
-        .take(6)
-        .map(char::from)
-        .collect()
+    let alphabet_len = ALPHABET.len();
+    let mask = (2usize << (usize::BITS - 1 - (alphabet_len - 1).leading_zeros())) - 1;
+
+    let mut id = String::with_capacity(len);
+    let mut byte = [0u8; 1];
+
+    while id.len() < len {
+        rng.fill_bytes(&mut byte);
+        let idx = byte[0] as usize & mask;
+
+        if idx < alphabet_len {
+            id.push(ALPHABET[idx]);
+        }
+    }
+
+    id
 }
 
-pub async fn get_url_by_id(conn: &Connection, id: &str) -> Result<Option<String>, rusqlite::Error> {
-    let mut stmt = conn.prepare("select url from urls where id = ? limit 1")?;
-    stmt.query_row(&[id], |r| r.get(0)).optional()
+fn is_unique_violation(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::ConstraintViolation
+    )
 }
 
-pub async fn get_id_by_url(conn: &Connection, url: &str) -> Result<Option<String>, rusqlite::Error> {
-    let mut stmt = conn.prepare("select id from urls where url = ? limit 1")?;
-    stmt.query_row(&[url], |r| r.get(0)).optional()
+fn insert_url(
+    conn: &Connection,
+    id: &str,
+    url: &str,
+    owner_id: Option<i64>,
+    expires_at: Option<&str>,
+    max_hits: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "insert into urls (id, url, owner_id, expires_at, max_hits) values (?1, ?2, ?3, ?4, ?5)",
+        params![id, url, owner_id, expires_at, max_hits],
+    )?;
+    Ok(())
 }
 
-pub async fn add_url(conn: &Connection, url: &str) -> Result<String, rusqlite::Error> {
-    let mut stmt = conn.prepare("insert into urls (id, url) values (?, ?)")?;
+/// Outcome of [`record_hit`]: whether the link is still alive (and where it
+/// points), past its deadline/budget, or doesn't exist at all.
+#[derive(Debug)]
+pub enum HitOutcome {
+    Alive(String),
+    Dead,
+    NotFound,
+}
+
+/// Atomically checks a link's deadline/budget and, if it's still alive,
+/// counts this access against it — a single `update ... where` so two
+/// concurrent requests against a `max_hits = 1` link can't both read the
+/// old hit count, both pass the check, and both get redirected before
+/// either increment lands. A zero-row update means the link is either dead
+/// or missing; a follow-up select tells which.
+pub async fn record_hit(pool: &Pool, id: &str) -> Result<HitOutcome, AppError> {
+    let pool = pool.clone();
+    let id = id.to_string();
+
+    let result = web::block(move || -> Result<HitOutcome, AppError> {
+        let conn = pool.get()?;
+        let now = chrono::Utc::now().to_rfc3339();
 
-    let id = generate_id();
+        let affected = conn.execute(
+            "update urls set hits = hits + 1
+             where id = ?1
+               and (expires_at is null or expires_at > ?2)
+               and (max_hits is null or hits < max_hits)",
+            params![id, now],
+        )?;
 
-    stmt.execute(&[id.clone(), url.to_string()])?;
+        if affected > 0 {
+            let url = conn.query_row("select url from urls where id = ?", &[&id], |r| r.get(0))?;
+            return Ok(HitOutcome::Alive(url));
+        }
 
-    Ok(id)
+        let exists: Option<String> = conn
+            .query_row("select url from urls where id = ?", &[&id], |r| r.get(0))
+            .optional()?;
+
+        Ok(match exists {
+            Some(_) => HitOutcome::Dead,
+            None => HitOutcome::NotFound,
+        })
+    })
+    .await;
+
+    flatten_blocking(result)
 }
 
-pub async fn add_url_hit(conn: &Connection, id: &str) -> Result<(), rusqlite::Error> {
-    let mut stmt = conn.prepare("update urls set hits = hits + 1 where id = ?")?;
-    stmt.execute(&[id.clone()])?;
-    Ok(())
+/// Removes a dead (expired or click-exhausted) link. Called lazily when an
+/// access finds the link past its deadline or budget.
+pub async fn delete_dead_url(pool: &Pool, id: &str) -> Result<(), AppError> {
+    let pool = pool.clone();
+    let id = id.to_string();
+
+    let result = web::block(move || -> Result<(), AppError> {
+        let conn = pool.get()?;
+        conn.execute("delete from urls where id = ?", &[&id])?;
+        Ok(())
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+/// Finds a pre-existing *plain* row for `url` to dedup a bare shortening
+/// request against. Only matches rows with no owner, expiry, or hit budget
+/// of their own: handing back someone else's owned row, or one that is
+/// expiring/one-shot, would silently saddle an unrelated caller with
+/// restrictions they never asked for.
+pub async fn get_id_by_url(pool: &Pool, url: &str) -> Result<Option<String>, AppError> {
+    let pool = pool.clone();
+    let url = url.to_string();
+
+    let result = web::block(move || -> Result<Option<String>, AppError> {
+        let conn = pool.get()?;
+        let mut stmt = conn.prepare(
+            "select id from urls
+             where url = ? and owner_id is null and expires_at is null and max_hits is null
+             limit 1",
+        )?;
+        Ok(stmt.query_row(&[&url], |r| r.get(0)).optional()?)
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+/// Inserts `url` under a freshly generated id of length `id_len`, or under
+/// `alias` if one is given, optionally owned by `owner_id` and optionally
+/// bounded by `expires_at` (an RFC 3339 timestamp) and/or `max_hits`. A
+/// requested `alias` that is already taken is rejected with
+/// [`AppError::AliasTaken`]; a generated id that collides is silently retried.
+///
+/// `url` is intentionally not unique (see migration 5): the same url can be
+/// shortened under several ids, so the only constraint insertion can trip is
+/// the `id`/`alias` primary key, which is what makes `is_unique_violation`
+/// safe to read as "that alias is taken".
+#[allow(clippy::too_many_arguments)]
+pub async fn add_url(
+    pool: &Pool,
+    url: &str,
+    alias: Option<&str>,
+    id_len: usize,
+    owner_id: Option<i64>,
+    expires_at: Option<&str>,
+    max_hits: Option<i64>,
+) -> Result<String, AppError> {
+    let pool = pool.clone();
+    let url = url.to_string();
+    let alias = alias.map(str::to_string);
+    let expires_at = expires_at.map(str::to_string);
+
+    let result = web::block(move || -> Result<String, AppError> {
+        let conn = pool.get()?;
+
+        if let Some(alias) = alias {
+            return match insert_url(&conn, &alias, &url, owner_id, expires_at.as_deref(), max_hits)
+            {
+                Ok(()) => Ok(alias),
+                Err(e) if is_unique_violation(&e) => Err(AppError::AliasTaken),
+                Err(e) => Err(e.into()),
+            };
+        }
+
+        for _ in 0..MAX_ID_GENERATION_ATTEMPTS {
+            let id = generate_id(id_len);
+
+            match insert_url(&conn, &id, &url, owner_id, expires_at.as_deref(), max_hits) {
+                Ok(()) => return Ok(id),
+                Err(e) if is_unique_violation(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(AppError::SqlError(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CONSTRAINT),
+            Some("exhausted id generation attempts".to_string()),
+        )))
+    })
+    .await;
+
+    flatten_blocking(result)
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct UrlRecord {
+    pub id: String,
+    pub url: String,
+    pub hits: i64,
+    pub created_at: String,
+}
+
+pub async fn list_urls_by_owner(pool: &Pool, owner_id: i64) -> Result<Vec<UrlRecord>, AppError> {
+    let pool = pool.clone();
+
+    let result = web::block(move || -> Result<Vec<UrlRecord>, AppError> {
+        let conn = pool.get()?;
+        let mut stmt = conn.prepare(
+            "select id, url, hits, created_at from urls where owner_id = ? order by created_at desc",
+        )?;
+        let rows = stmt.query_map(&[owner_id], |r| {
+            Ok(UrlRecord {
+                id: r.get(0)?,
+                url: r.get(1)?,
+                hits: r.get(2)?,
+                created_at: r.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+/// Deletes the url `id` if it is owned by `owner_id`, returning whether a row was removed.
+pub async fn delete_url(pool: &Pool, id: &str, owner_id: i64) -> Result<bool, AppError> {
+    let pool = pool.clone();
+    let id = id.to_string();
+
+    let result = web::block(move || -> Result<bool, AppError> {
+        let conn = pool.get()?;
+        let affected = conn.execute(
+            "delete from urls where id = ?1 and owner_id = ?2",
+            params![id, owner_id],
+        )?;
+        Ok(affected > 0)
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+/// Inserts a new user. A `username` that's already taken (including one
+/// that raced in between the caller's own existence check and this insert)
+/// is rejected with [`AppError::UsernameTaken`] rather than the generic
+/// `SqlError`, the same way `add_url` catches an alias collision.
+pub async fn add_user(pool: &Pool, username: &str, password_hash: &str) -> Result<i64, AppError> {
+    let pool = pool.clone();
+    let username = username.to_string();
+    let password_hash = password_hash.to_string();
+
+    let result = web::block(move || -> Result<i64, AppError> {
+        let conn = pool.get()?;
+        match conn.execute(
+            "insert into users (username, password_hash) values (?1, ?2)",
+            params![username, password_hash],
+        ) {
+            Ok(_) => Ok(conn.last_insert_rowid()),
+            Err(e) if is_unique_violation(&e) => Err(AppError::UsernameTaken),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+pub async fn get_user_by_username(pool: &Pool, username: &str) -> Result<Option<User>, AppError> {
+    let pool = pool.clone();
+    let username = username.to_string();
+
+    let result = web::block(move || -> Result<Option<User>, AppError> {
+        let conn = pool.get()?;
+        let mut stmt = conn
+            .prepare("select id, username, password_hash from users where username = ? limit 1")?;
+        Ok(stmt
+            .query_row(&[&username], |r| {
+                Ok(User {
+                    id: r.get(0)?,
+                    username: r.get(1)?,
+                    password_hash: r.get(2)?,
+                })
+            })
+            .optional()?)
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+pub async fn add_token(pool: &Pool, token: &str, user_id: i64) -> Result<(), AppError> {
+    let pool = pool.clone();
+    let token = token.to_string();
+
+    let result = web::block(move || -> Result<(), AppError> {
+        let conn = pool.get()?;
+        conn.execute(
+            "insert into tokens (token, user_id) values (?1, ?2)",
+            params![token, user_id],
+        )?;
+        Ok(())
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+pub async fn get_user_by_token(pool: &Pool, token: &str) -> Result<Option<User>, AppError> {
+    let pool = pool.clone();
+    let token = token.to_string();
+
+    let result = web::block(move || -> Result<Option<User>, AppError> {
+        let conn = pool.get()?;
+        let mut stmt = conn.prepare(
+            "select users.id, users.username, users.password_hash
+             from tokens
+             join users on users.id = tokens.user_id
+             where tokens.token = ?
+             limit 1",
+        )?;
+        Ok(stmt
+            .query_row(&[&token], |r| {
+                Ok(User {
+                    id: r.get(0)?,
+                    username: r.get(1)?,
+                    password_hash: r.get(2)?,
+                })
+            })
+            .optional()?)
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+pub async fn record_click(
+    pool: &Pool,
+    url_id: &str,
+    referer: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<(), AppError> {
+    let pool = pool.clone();
+    let url_id = url_id.to_string();
+    let referer = referer.map(str::to_string);
+    let user_agent = user_agent.map(str::to_string);
+
+    let result = web::block(move || -> Result<(), AppError> {
+        let conn = pool.get()?;
+        conn.execute(
+            "insert into clicks (url_id, referer, user_agent) values (?1, ?2, ?3)",
+            params![url_id, referer, user_agent],
+        )?;
+        Ok(())
+    })
+    .await;
+
+    flatten_blocking(result)
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct DailyClicks {
+    pub day: String,
+    pub count: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct UrlStats {
+    pub url: String,
+    pub hits: i64,
+    pub created_at: String,
+    pub clicks_by_day: Vec<DailyClicks>,
+}
+
+/// Returns stats for url `id` iff it is owned by `owner_id`, matching the
+/// ownership gate used by [`delete_url`].
+pub async fn get_stats_by_id(
+    pool: &Pool,
+    id: &str,
+    owner_id: i64,
+) -> Result<Option<UrlStats>, AppError> {
+    let pool = pool.clone();
+    let id = id.to_string();
+
+    let result = web::block(move || -> Result<Option<UrlStats>, AppError> {
+        let conn = pool.get()?;
+
+        let mut stmt = conn
+            .prepare("select url, hits, created_at from urls where id = ?1 and owner_id = ?2 limit 1")?;
+        let base = stmt
+            .query_row(params![id, owner_id], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, i64>(1)?,
+                    r.get::<_, String>(2)?,
+                ))
+            })
+            .optional()?;
+
+        let (url, hits, created_at) = match base {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let mut stmt = conn.prepare(
+            "select date(clicked_at) as day, count(*) from clicks where url_id = ? group by day order by day",
+        )?;
+        let clicks_by_day = stmt
+            .query_map(&[&id], |r| {
+                Ok(DailyClicks {
+                    day: r.get(0)?,
+                    count: r.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(UrlStats {
+            url,
+            hits,
+            created_at,
+            clicks_by_day,
+        }))
+    })
+    .await;
+
+    flatten_blocking(result)
+}
@@ -12,12 +12,32 @@ pub enum AppError {
     SqlError(#[from] #[source] rusqlite::Error),
     #[error("validation errors {0}")]
     ValidationErrors(#[from] #[source] ValidationErrors),
+    #[error("password hash error {0}")]
+    PasswordHashError(#[from] #[source] argon2::Error),
+    #[error("alias already taken")]
+    AliasTaken,
+    #[error("username already taken")]
+    UsernameTaken,
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("invalid expires_at")]
+    InvalidExpiry,
+    #[error("gone")]
+    Gone,
+    #[error("blocking task canceled")]
+    BlockingCanceled,
 }
 
 impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
         match self {
-            Self::ValidationErrors(_) => StatusCode::BAD_REQUEST,
+            Self::ValidationErrors(_) | Self::InvalidExpiry => StatusCode::BAD_REQUEST,
+            Self::AliasTaken | Self::UsernameTaken => StatusCode::CONFLICT,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Gone => StatusCode::GONE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -27,6 +47,12 @@ impl ResponseError for AppError {
             Self::ValidationErrors(e) => {
                 HttpResponse::BadRequest().json(e.errors())
             }
+            Self::InvalidExpiry => HttpResponse::BadRequest().body("invalid expires_at."),
+            Self::AliasTaken => HttpResponse::Conflict().body("alias already taken."),
+            Self::UsernameTaken => HttpResponse::Conflict().body("username already taken."),
+            Self::Unauthorized => HttpResponse::Unauthorized().body("unauthorized."),
+            Self::Forbidden => HttpResponse::Forbidden().body("forbidden."),
+            Self::Gone => HttpResponse::Gone().body("this link is no longer available."),
             e => {
                 log::error!("internal server error: {}", e);
                 HttpResponse::InternalServerError().body("Internal server error.")
@@ -0,0 +1,40 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// Parses an `expires_at` field from `UrlPayload`, which may be either an
+/// RFC 3339 absolute timestamp (`2025-01-01T00:00:00Z`) or a relative
+/// duration (`30m`, `2h`, `7d`, `1w`). Returns `None` if neither parses.
+pub fn parse_expires_at(input: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    parse_relative_duration(input).and_then(|d| Utc::now().checked_add_signed(d))
+}
+
+// The largest `seconds` value `Duration::seconds` can hold without tripping
+// its internal bounds check.
+const MAX_DURATION_SECONDS: i64 = i64::MAX / 1_000;
+
+fn parse_relative_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    let unit = input.chars().last()?;
+    let amount: i64 = input[..input.len() - unit.len_utf8()].parse().ok()?;
+
+    let seconds_per_unit: i64 = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 60 * 60 * 24,
+        'w' => 60 * 60 * 24 * 7,
+        _ => return None,
+    };
+
+    // `amount * seconds_per_unit` can overflow for adversarially large
+    // inputs (e.g. `expires_at=99999999999999w`); reject rather than panic.
+    let seconds = amount.checked_mul(seconds_per_unit)?;
+    if !(0..=MAX_DURATION_SECONDS).contains(&seconds) {
+        return None;
+    }
+
+    Some(Duration::seconds(seconds))
+}
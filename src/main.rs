@@ -1,65 +1,213 @@
 use actix_ratelimit::{MemoryStore, MemoryStoreActor, RateLimiter};
-use actix_web::{http, middleware, web, App, HttpResponse, HttpServer};
+use actix_web::{http, middleware, web, App, HttpRequest, HttpResponse, HttpServer};
+use auth::AuthedUser;
 use clap::{App as CApp, AppSettings, Arg};
 use errors::AppError;
 use r2d2_sqlite::{self, SqliteConnectionManager};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use validator::Validate;
 
+mod auth;
 mod db;
 mod errors;
+mod expiry;
+mod migrations;
 
 type AppResponse = Result<HttpResponse, AppError>;
 
 #[derive(Debug, Clone)]
 pub struct RootUrl {
     url: String,
+    id_length: usize,
 }
 
-async fn get_url(pool: web::Data<db::Pool>, web::Path(id): web::Path<String>) -> AppResponse {
-    let conn = pool.get()?;
+async fn get_url(
+    pool: web::Data<db::Pool>,
+    web::Path(id): web::Path<String>,
+    req: HttpRequest,
+) -> AppResponse {
+    let res = match db::record_hit(&pool, &id).await? {
+        db::HitOutcome::Dead => {
+            db::delete_dead_url(&pool, &id).await?;
+            return Err(AppError::Gone);
+        }
+        db::HitOutcome::Alive(url) => {
+            let referer = req
+                .headers()
+                .get(http::header::REFERER)
+                .and_then(|h| h.to_str().ok());
+            let user_agent = req
+                .headers()
+                .get(http::header::USER_AGENT)
+                .and_then(|h| h.to_str().ok());
+            db::record_click(&pool, &id, referer, user_agent).await?;
+
+            HttpResponse::Found()
+                .set_header(http::header::LOCATION, url)
+                .finish()
+        }
+        db::HitOutcome::NotFound => HttpResponse::NotFound().finish(),
+    };
+    Ok(res)
+}
 
-    let url = db::get_url_by_id(&conn, &id).await?;
+async fn get_stats(
+    pool: web::Data<db::Pool>,
+    web::Path(id): web::Path<String>,
+    user: AuthedUser,
+) -> AppResponse {
+    match db::get_stats_by_id(&pool, &id, user.0.id).await? {
+        Some(stats) => Ok(HttpResponse::Ok().json(stats)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
 
-    let res;
+// Top-level resources registered in `main()`; an alias matching one of these
+// would never be reachable since actix matches the literal route first.
+const RESERVED_ALIASES: &[&str] = &["register", "login", "urls"];
 
-    if let Some(url) = url {
-        res = HttpResponse::Found()
-            .set_header(http::header::LOCATION, url)
-            .finish();
-    } else {
-        res = HttpResponse::NotFound().finish()
+fn validate_alias(alias: &str) -> Result<(), validator::ValidationError> {
+    let charset_ok = alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !charset_ok {
+        return Err(validator::ValidationError::new("alias_charset"));
     }
-    Ok(res)
+
+    if RESERVED_ALIASES.contains(&alias.to_ascii_lowercase().as_str()) {
+        return Err(validator::ValidationError::new("alias_reserved"));
+    }
+
+    Ok(())
 }
 
 #[derive(Debug, Deserialize, Validate)]
 struct UrlPayload {
     #[validate(url)]
     url: String,
+    #[validate(length(min = 1, max = 32), custom = "validate_alias")]
+    alias: Option<String>,
+    /// An RFC 3339 timestamp or a relative duration (`30m`, `2h`, `7d`, `1w`).
+    expires_at: Option<String>,
+    #[validate(range(min = 1))]
+    max_hits: Option<i64>,
 }
 
 async fn add_url(
     pool: web::Data<db::Pool>,
     data: web::Form<UrlPayload>,
     root: web::Data<RootUrl>,
+    user: Option<AuthedUser>,
 ) -> AppResponse {
     data.validate()?;
 
-    let conn = pool.get()?;
+    let owner_id = user.map(|u| u.0.id);
+
+    let expires_at = data
+        .expires_at
+        .as_deref()
+        .map(|raw| expiry::parse_expires_at(raw).ok_or(AppError::InvalidExpiry))
+        .transpose()?
+        .map(|dt| dt.to_rfc3339());
+
+    // Reusing a pre-existing id for this url is only safe when the caller
+    // isn't asking for anything that old row might not have: an alias, an
+    // expiry/hit budget, or ownership. Any of those needs a fresh row of
+    // its own, or the option would be silently dropped on the floor.
+    // `get_id_by_url` enforces the other half of this: it only ever matches
+    // rows that are themselves plain, so a bare request can't be handed
+    // someone else's owned or bounded row either.
+    let wants_fresh_row =
+        data.alias.is_some() || expires_at.is_some() || data.max_hits.is_some() || owner_id.is_some();
+
+    let id = if !wants_fresh_row {
+        match db::get_id_by_url(&pool, &data.url).await? {
+            Some(id) => id,
+            None => db::add_url(&pool, &data.url, None, root.id_length, None, None, None).await?,
+        }
+    } else {
+        db::add_url(
+            &pool,
+            &data.url,
+            data.alias.as_deref(),
+            root.id_length,
+            owner_id,
+            expires_at.as_deref(),
+            data.max_hits,
+        )
+        .await?
+    };
 
-    let id;
+    Ok(HttpResponse::Created().body(format!("{}/{}", root.url, id)))
+}
 
-    if let Some(u) = db::get_id_by_url(&conn, &data.url).await? {
-        id = u;
-    } else {
-        id = db::add_url(&conn, &data.url).await?;
+#[derive(Debug, Deserialize, Validate)]
+struct RegisterPayload {
+    #[validate(length(min = 3, max = 32))]
+    username: String,
+    #[validate(length(min = 8))]
+    password: String,
+}
+
+async fn register(pool: web::Data<db::Pool>, data: web::Form<RegisterPayload>) -> AppResponse {
+    data.validate()?;
+
+    if db::get_user_by_username(&pool, &data.username)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::UsernameTaken);
     }
 
-    db::add_url_hit(&conn, &id).await?;
+    let password_hash = auth::hash_password(&data.password)?;
+    db::add_user(&pool, &data.username, &password_hash).await?;
 
-    Ok(HttpResponse::Created().body(format!("{}/{}", root.url, id)))
+    Ok(HttpResponse::Created().finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginPayload {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenResponse {
+    token: String,
+}
+
+async fn login(pool: web::Data<db::Pool>, data: web::Form<LoginPayload>) -> AppResponse {
+    let user = db::get_user_by_username(&pool, &data.username)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+
+    if !auth::verify_password(&user.password_hash, &data.password)? {
+        return Err(AppError::Unauthorized);
+    }
+
+    let token = auth::generate_token();
+    db::add_token(&pool, &token, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(TokenResponse { token }))
+}
+
+async fn list_urls(pool: web::Data<db::Pool>, user: AuthedUser) -> AppResponse {
+    let urls = db::list_urls_by_owner(&pool, user.0.id).await?;
+    Ok(HttpResponse::Ok().json(urls))
+}
+
+async fn delete_url(
+    pool: web::Data<db::Pool>,
+    web::Path(id): web::Path<String>,
+    user: AuthedUser,
+) -> AppResponse {
+    if db::delete_url(&pool, &id, user.0.id).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::Forbidden)
+    }
 }
 
 #[actix_web::main]
@@ -80,23 +228,33 @@ async fn main() -> std::io::Result<()> {
                 .about("The database name.")
                 .default_value("urls.db"),
         )
+        .arg(
+            Arg::new("id_length")
+                .long("id-length")
+                .about("Length of generated short ids.")
+                .default_value("6"),
+        )
         .get_matches();
 
     let root_url = matches.value_of("root").unwrap();
     let port = matches.value_of("port").unwrap();
     let db_name = matches.value_of("db_name").unwrap();
+    let id_length: usize = matches
+        .value_of("id_length")
+        .unwrap()
+        .parse()
+        .expect("id-length must be a positive integer");
 
     let manager = SqliteConnectionManager::file(&db_name);
     let pool = db::Pool::new(manager).unwrap();
 
-    db::create_table(&pool.get().unwrap())
-        .await
-        .expect("error creating tables");
+    migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
 
     let store = MemoryStore::new();
 
     let root_url = RootUrl {
         url: root_url.to_string(),
+        id_length,
     };
 
     HttpServer::new(move || {
@@ -109,7 +267,15 @@ async fn main() -> std::io::Result<()> {
                     .with_interval(Duration::from_secs(60))
                     .with_max_requests(100),
             )
-            .service(web::resource("/{id}").route(web::get().to(get_url)))
+            .service(web::resource("/register").route(web::post().to(register)))
+            .service(web::resource("/login").route(web::post().to(login)))
+            .service(web::resource("/urls").route(web::get().to(list_urls)))
+            .service(web::resource("/{id}/stats").route(web::get().to(get_stats)))
+            .service(
+                web::resource("/{id}")
+                    .route(web::get().to(get_url))
+                    .route(web::delete().to(delete_url)),
+            )
             .service(
                 web::resource("/")
                     .route(web::post().to(add_url))
@@ -135,14 +301,13 @@ mod tests {
     async fn add_valid_url() {
         let root_url = RootUrl {
             url: "http://localhost".to_owned(),
+            id_length: 6,
         };
 
         let manager = SqliteConnectionManager::file(":memory:");
         let pool = db::Pool::new(manager).unwrap();
 
-        db::create_table(&pool.get().unwrap())
-            .await
-            .expect("error creating tables");
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
 
         let mut app = test::init_service(
             App::new()
@@ -162,14 +327,13 @@ mod tests {
     async fn add_invalid_url() {
         let root_url = RootUrl {
             url: "http://localhost".to_owned(),
+            id_length: 6,
         };
 
         let manager = SqliteConnectionManager::file(":memory:");
         let pool = db::Pool::new(manager).unwrap();
 
-        db::create_table(&pool.get().unwrap())
-            .await
-            .expect("error creating tables");
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
 
         let mut app = test::init_service(
             App::new()
@@ -190,14 +354,13 @@ mod tests {
     async fn added_url_redirects() {
         let root_url = RootUrl {
             url: "http://localhost".to_owned(),
+            id_length: 6,
         };
 
         let manager = SqliteConnectionManager::file(":memory:");
         let pool = db::Pool::new(manager).unwrap();
 
-        db::create_table(&pool.get().unwrap())
-            .await
-            .expect("error creating tables");
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
 
         let mut app = test::init_service(
             App::new()
@@ -223,4 +386,579 @@ mod tests {
         let resp = test::call_service(&mut app, req).await;
         assert!(resp.status().is_redirection());
     }
+
+    #[actix_rt::test]
+    async fn expired_link_is_gone() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url))
+                .route("/{id}", web::get().to(get_url)),
+        )
+        .await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        form.insert("expires_at", "2000-01-01T00:00:00Z");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+
+        let body = test::read_body(resp).await;
+        let url_returned: &str = std::str::from_utf8(&body).unwrap();
+        let id = url_returned.replace("http://localhost/", "");
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/{}", id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::GONE);
+    }
+
+    #[actix_rt::test]
+    async fn one_shot_link_is_gone_after_its_single_hit() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url))
+                .route("/{id}", web::get().to(get_url)),
+        )
+        .await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        form.insert("max_hits", "1");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+
+        let body = test::read_body(resp).await;
+        let url_returned: &str = std::str::from_utf8(&body).unwrap();
+        let id = url_returned.replace("http://localhost/", "");
+
+        // The check-and-increment is a single atomic update, so the one
+        // allotted hit redirects...
+        let req = test::TestRequest::get()
+            .uri(&format!("/{}", id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_redirection());
+
+        // ...and every access after that sees the budget already spent.
+        let req = test::TestRequest::get()
+            .uri(&format!("/{}", id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::GONE);
+    }
+
+    #[actix_rt::test]
+    async fn oversized_relative_expiry_is_rejected_not_panicked() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url)),
+        )
+        .await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        form.insert("expires_at", "99999999999999w");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn add_url_with_expiry_does_not_reuse_an_unbounded_row() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url)),
+        )
+        .await;
+
+        // First request shortens the url with no expiry.
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = test::read_body(resp).await;
+        let first_id = std::str::from_utf8(&body)
+            .unwrap()
+            .replace("http://localhost/", "");
+
+        // A later request asking for a one-shot/expiring link on the same
+        // url must get its own row rather than silently reusing the
+        // unbounded one, or the expiry/max_hits it asked for would never
+        // take effect.
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        form.insert("expires_at", "1m");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+        let body = test::read_body(resp).await;
+        let second_id = std::str::from_utf8(&body)
+            .unwrap()
+            .replace("http://localhost/", "");
+
+        assert_ne!(first_id, second_id);
+    }
+
+    #[actix_rt::test]
+    async fn plain_add_url_does_not_reuse_an_existing_bounded_row() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url)),
+        )
+        .await;
+
+        // Caller A gets a one-shot link for this url.
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        form.insert("max_hits", "1");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = test::read_body(resp).await;
+        let one_shot_id = std::str::from_utf8(&body)
+            .unwrap()
+            .replace("http://localhost/", "");
+
+        // Caller B, unrelated, shortens the same url with no options of
+        // their own. They must not be handed A's one-shot row, or they'd
+        // inherit a click budget they never asked for.
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+        let body = test::read_body(resp).await;
+        let plain_id = std::str::from_utf8(&body)
+            .unwrap()
+            .replace("http://localhost/", "");
+
+        assert_ne!(one_shot_id, plain_id);
+    }
+
+    #[actix_rt::test]
+    async fn alias_for_already_shortened_url_is_not_falsely_rejected() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url)),
+        )
+        .await;
+
+        // Someone already shortened this url under a generated id.
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+
+        // A later request picks a fresh, unused alias for the same url. The
+        // alias itself isn't taken, so this must succeed rather than being
+        // misreported as AliasTaken by the url's old unique constraint.
+        let mut form = HashMap::new();
+        form.insert("url", "http://somedomain.com");
+        form.insert("alias", "myalias");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn duplicate_alias_is_rejected() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url)),
+        )
+        .await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://example.com");
+        form.insert("alias", "taken");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://another.com");
+        form.insert("alias", "taken");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+    }
+
+    #[actix_rt::test]
+    async fn reserved_alias_is_rejected() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url)),
+        )
+        .await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://example.com");
+        form.insert("alias", "login");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_client_error());
+    }
+
+    #[actix_rt::test]
+    async fn stats_requires_link_ownership() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/register", web::post().to(register))
+                .route("/login", web::post().to(login))
+                .route("/", web::post().to(add_url))
+                .route("/{id}/stats", web::get().to(get_stats)),
+        )
+        .await;
+
+        let mut form = HashMap::new();
+        form.insert("username", "owner");
+        form.insert("password", "hunter22");
+        let req = test::TestRequest::post()
+            .uri("/register")
+            .set_form(&form)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_form(&form)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let token: TokenResponse = test::read_body_json(resp).await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://owned.example.com");
+        let req = test::TestRequest::post()
+            .set_form(&form)
+            .header("Authorization", format!("Bearer {}", token.token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+        let body = test::read_body(resp).await;
+        let url_returned: &str = std::str::from_utf8(&body).unwrap();
+        let id = url_returned.replace("http://localhost/", "");
+
+        // The owner can read stats.
+        let req = test::TestRequest::get()
+            .uri(&format!("/{}/stats", id))
+            .header("Authorization", format!("Bearer {}", token.token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+
+        // An unauthenticated request cannot.
+        let req = test::TestRequest::get()
+            .uri(&format!("/{}/stats", id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn migrations_run_is_idempotent() {
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("first run should apply every migration");
+        migrations::run(&mut pool.get().unwrap())
+            .expect("second run against an up-to-date database should be a no-op, not an error");
+    }
+
+    #[actix_rt::test]
+    async fn list_urls_requires_authentication() {
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(pool.clone())
+                .route("/urls", web::get().to(list_urls)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/urls").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn delete_url_forbidden_for_non_owner() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/register", web::post().to(register))
+                .route("/login", web::post().to(login))
+                .route("/", web::post().to(add_url))
+                .route("/{id}", web::delete().to(delete_url)),
+        )
+        .await;
+
+        let mut owner_form = HashMap::new();
+        owner_form.insert("username", "owner");
+        owner_form.insert("password", "hunter22");
+        let req = test::TestRequest::post()
+            .uri("/register")
+            .set_form(&owner_form)
+            .to_request();
+        test::call_service(&mut app, req).await;
+
+        let mut other_form = HashMap::new();
+        other_form.insert("username", "someone-else");
+        other_form.insert("password", "hunter22");
+        let req = test::TestRequest::post()
+            .uri("/register")
+            .set_form(&other_form)
+            .to_request();
+        test::call_service(&mut app, req).await;
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_form(&owner_form)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let owner_token: TokenResponse = test::read_body_json(resp).await;
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_form(&other_form)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let other_token: TokenResponse = test::read_body_json(resp).await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://owned.example.com");
+        let req = test::TestRequest::post()
+            .set_form(&form)
+            .header("Authorization", format!("Bearer {}", owner_token.token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = test::read_body(resp).await;
+        let url_returned: &str = std::str::from_utf8(&body).unwrap();
+        let id = url_returned.replace("http://localhost/", "");
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/{}", id))
+            .header("Authorization", format!("Bearer {}", other_token.token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn authenticated_add_url_does_not_reuse_anonymous_row() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/register", web::post().to(register))
+                .route("/login", web::post().to(login))
+                .route("/", web::post().to(add_url))
+                .route("/urls", web::get().to(list_urls)),
+        )
+        .await;
+
+        // Someone shortens this url anonymously first.
+        let mut form = HashMap::new();
+        form.insert("url", "http://shared.example.com");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        test::call_service(&mut app, req).await;
+
+        let mut credentials = HashMap::new();
+        credentials.insert("username", "owner");
+        credentials.insert("password", "hunter22");
+        let req = test::TestRequest::post()
+            .uri("/register")
+            .set_form(&credentials)
+            .to_request();
+        test::call_service(&mut app, req).await;
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_form(&credentials)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let token: TokenResponse = test::read_body_json(resp).await;
+
+        // The authenticated request for the same url must get its own,
+        // owned row rather than silently being handed the anonymous one.
+        let mut form = HashMap::new();
+        form.insert("url", "http://shared.example.com");
+        let req = test::TestRequest::post()
+            .set_form(&form)
+            .header("Authorization", format!("Bearer {}", token.token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::get()
+            .uri("/urls")
+            .header("Authorization", format!("Bearer {}", token.token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let urls: Vec<db::UrlRecord> = test::read_body_json(resp).await;
+        assert_eq!(urls.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn alias_with_unsafe_charset_is_rejected() {
+        let root_url = RootUrl {
+            url: "http://localhost".to_owned(),
+            id_length: 6,
+        };
+
+        let manager = SqliteConnectionManager::file(":memory:");
+        let pool = db::Pool::new(manager).unwrap();
+
+        migrations::run(&mut pool.get().unwrap()).expect("error running migrations");
+
+        let mut app = test::init_service(
+            App::new()
+                .data(root_url)
+                .data(pool.clone())
+                .route("/", web::post().to(add_url)),
+        )
+        .await;
+
+        let mut form = HashMap::new();
+        form.insert("url", "http://example.com");
+        form.insert("alias", "../etc");
+        let req = test::TestRequest::post().set_form(&form).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.status().is_client_error());
+    }
 }
@@ -0,0 +1,106 @@
+use crate::db::Connection;
+use rusqlite::params;
+
+/// Ordered, append-only list of schema migrations. Each entry is applied
+/// exactly once, in a transaction, and bumps `schema_version`. Never edit an
+/// already-shipped entry — append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: the original urls table.
+    "
+    create table if not exists urls (
+        id text primary key,
+        url text not null unique,
+        hits bigint default 0
+    )
+    ",
+    // 2: user accounts, bearer tokens, and per-link ownership.
+    "
+    create table if not exists users (
+        id integer primary key autoincrement,
+        username text not null unique,
+        password_hash text not null
+    );
+
+    create table if not exists tokens (
+        token text primary key,
+        user_id integer not null references users(id),
+        created_at timestamp not null default current_timestamp
+    );
+
+    alter table urls add column owner_id integer references users(id);
+    alter table urls add column created_at timestamp not null default current_timestamp;
+    ",
+    // 3: per-redirect click analytics.
+    "
+    create table if not exists clicks (
+        id integer primary key autoincrement,
+        url_id text not null references urls(id),
+        clicked_at timestamp not null default current_timestamp,
+        referer text,
+        user_agent text
+    )
+    ",
+    // 4: expiring and one-shot self-destructing links.
+    "
+    alter table urls add column expires_at text;
+    alter table urls add column max_hits bigint;
+    ",
+    // 5: custom aliases mean the same url can legitimately be shortened
+    // under more than one id, so it can no longer be globally unique.
+    // SQLite has no `drop constraint`, so rebuild the table without it.
+    "
+    create table urls_new (
+        id text primary key,
+        url text not null,
+        hits bigint default 0,
+        owner_id integer references users(id),
+        created_at timestamp not null default current_timestamp,
+        expires_at text,
+        max_hits bigint
+    );
+
+    insert into urls_new (id, url, hits, owner_id, created_at, expires_at, max_hits)
+        select id, url, hits, owner_id, created_at, expires_at, max_hits from urls;
+
+    drop table urls;
+    alter table urls_new rename to urls;
+    ",
+];
+
+fn current_version(conn: &Connection) -> Result<i64, rusqlite::Error> {
+    conn.execute_batch(
+        "create table if not exists schema_version (version integer not null)",
+    )?;
+
+    conn.query_row(
+        "select coalesce((select version from schema_version limit 1), 0)",
+        rusqlite::NO_PARAMS,
+        |r| r.get(0),
+    )
+}
+
+/// Applies every migration newer than the database's current `schema_version`,
+/// each inside its own transaction. Safe to call on every boot, including
+/// against a fresh or already up-to-date database.
+pub fn run(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let current = current_version(conn)?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate() {
+        let version = (i + 1) as i64;
+
+        if version <= current {
+            continue;
+        }
+
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute("delete from schema_version", rusqlite::NO_PARAMS)?;
+        tx.execute(
+            "insert into schema_version (version) values (?1)",
+            params![version],
+        )?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}